@@ -0,0 +1,51 @@
+use std::env::var;
+
+use deadpool_postgres::tokio_postgres::Config as PgConfig;
+
+/// Runtime configuration, read once at startup from the environment so the
+/// service can be tuned per deployment instead of recompiled.
+pub struct Configuration {
+    pub db_host: String,
+    pub db_user: String,
+    pub db_name: String,
+    pub db_pass: String,
+    pub db_pool_size: usize,
+    pub http_port: u16,
+    pub workers: usize,
+    pub storage: String,
+}
+
+impl Configuration {
+    pub fn from_env() -> Self {
+        let cpus = num_cpus::get();
+
+        Self {
+            db_host: var("DB_HOST").unwrap_or_else(|_| "localhost".into()),
+            db_user: var("DB_USER").unwrap_or_else(|_| "postgres".into()),
+            db_name: var("DB_NAME").unwrap_or_else(|_| "postgres".into()),
+            db_pass: var("DB_PASS").unwrap_or_else(|_| "password".into()),
+            db_pool_size: var("DB_POOL_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| (cpus * 4).max(16)),
+            http_port: var("HTTP_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(80),
+            workers: var("WORKERS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(cpus),
+            storage: var("STORAGE").unwrap_or_else(|_| "postgres".into()),
+        }
+    }
+
+    pub fn pg_config(&self) -> PgConfig {
+        PgConfig::new()
+            .host(&self.db_host)
+            .user(&self.db_user)
+            .dbname(&self.db_name)
+            .password(&self.db_pass)
+            .to_owned()
+    }
+}
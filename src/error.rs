@@ -0,0 +1,93 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use deadpool_postgres::PoolError;
+use tokio_postgres::error::SqlState;
+
+/// The single error type handlers return; `ResponseError` maps each variant
+/// to the status code the endpoint should answer with, so handlers can just
+/// use `?` instead of `unwrap()`ing pool/DB/parse failures into a panic.
+#[derive(Debug)]
+pub enum MyError {
+    Pool(PoolError),
+    Db(tokio_postgres::Error),
+    Payload(actix_web::error::PayloadError),
+    BadId,
+    NotFound,
+    Unprocessable,
+    Unavailable,
+}
+
+impl fmt::Display for MyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MyError::Pool(e) => write!(f, "pool error: {e}"),
+            MyError::Db(e) => write!(f, "db error: {e}"),
+            MyError::Payload(e) => write!(f, "payload error: {e}"),
+            MyError::BadId => write!(f, "invalid id"),
+            MyError::NotFound => write!(f, "not found"),
+            MyError::Unprocessable => write!(f, "unprocessable entity"),
+            MyError::Unavailable => write!(f, "service unavailable"),
+        }
+    }
+}
+
+impl ResponseError for MyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MyError::Pool(_) | MyError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            MyError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // actix's own `ResponseError` impl for `PayloadError` already
+            // distinguishes client mistakes (413 oversized body, 411
+            // missing length, ...) from genuine server/transport failures;
+            // collapsing that to 500 would turn those into apparent server
+            // errors, so defer to it instead of picking one status for all.
+            MyError::Payload(e) => e.status_code(),
+            MyError::BadId => StatusCode::BAD_REQUEST,
+            MyError::NotFound => StatusCode::NOT_FOUND,
+            MyError::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).finish()
+    }
+}
+
+impl From<PoolError> for MyError {
+    fn from(e: PoolError) -> Self {
+        MyError::Pool(e)
+    }
+}
+
+impl From<actix_web::error::PayloadError> for MyError {
+    fn from(e: actix_web::error::PayloadError) -> Self {
+        MyError::Payload(e)
+    }
+}
+
+impl From<uuid::Error> for MyError {
+    fn from(_: uuid::Error) -> Self {
+        MyError::BadId
+    }
+}
+
+impl From<tokio_postgres::Error> for MyError {
+    // Unique/integrity violations are a client mistake (duplicate
+    // `apelido`, missing required field), not a server failure, so they
+    // fold into `Unprocessable` here instead of every call site re-checking
+    // `e.code()`. Note that `create_person`'s own duplicate-`apelido` check
+    // no longer goes through this path (it's a synchronous `SELECT` that
+    // returns `Unprocessable` directly, and the write-behind batch insert
+    // uses `on conflict do nothing`, which can't raise a unique violation)
+    // — this mapping is kept for any other write path that does a plain
+    // insert/update against a constrained column.
+    fn from(e: tokio_postgres::Error) -> Self {
+        match e.code() {
+            Some(&SqlState::UNIQUE_VIOLATION) | Some(&SqlState::INTEGRITY_CONSTRAINT_VIOLATION) => {
+                MyError::Unprocessable
+            }
+            _ => MyError::Db(e),
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use std::{env::var, ops::DerefMut};
+use std::{ops::DerefMut, sync::Arc};
 
 use actix_web::{web, App, HttpServer};
-use deadpool_postgres::{tokio_postgres::Config, Manager, ManagerConfig, Pool, RecyclingMethod};
+use config::Configuration;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
 use handlers::{count_people, create_person, get_person, search_people};
+use repo::{InMemoryRepo, PeopleRepo, PostgresRepo};
+use tokio::task::JoinHandle;
 use tokio_postgres::NoTls;
 
 mod embedded {
@@ -10,6 +13,249 @@ mod embedded {
     embed_migrations!("./migrations");
 }
 
+mod config;
+mod error;
+mod repo;
+
+// Write-behind buffer for `create_person`: the handler hands the row off to
+// this task instead of doing a synchronous insert per request, trading
+// immediate durability for throughput under write bursts.
+mod flusher {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use deadpool_postgres::Pool;
+    use tokio::{sync::mpsc, task::JoinHandle, time::interval};
+    use uuid::Uuid;
+
+    use crate::models::PersonInput;
+
+    pub type FlushItem = (Uuid, PersonInput);
+    pub type FlushSender = mpsc::Sender<FlushItem>;
+
+    /// `apelido`s that a handler has already claimed (via
+    /// [`crate::repo::PostgresRepo::insert`]) but that haven't made it
+    /// through a flush yet, so a second request for the same never-before-seen
+    /// `apelido` can be rejected before it's ever enqueued instead of only
+    /// after the fact, when the batch insert drops one of the two.
+    pub type InflightSet = Mutex<HashSet<String>>;
+
+    const BATCH_SIZE: usize = 256;
+    const BATCH_WINDOW: Duration = Duration::from_millis(5);
+    const CHANNEL_CAPACITY: usize = 4096;
+
+    /// Spawns the background flusher and returns the sender handlers push
+    /// new people onto, plus a join handle that resolves once the sender
+    /// side is dropped and the final batch has been flushed.
+    pub fn spawn(pool: Pool, inflight: Arc<InflightSet>) -> (FlushSender, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<FlushItem>(CHANNEL_CAPACITY);
+
+        let handle = tokio::spawn(async move {
+            let mut buf: Vec<FlushItem> = Vec::with_capacity(BATCH_SIZE);
+            let mut ticker = interval(BATCH_WINDOW);
+
+            loop {
+                tokio::select! {
+                    item = rx.recv() => {
+                        match item {
+                            Some(item) => {
+                                buf.push(item);
+                                if buf.len() >= BATCH_SIZE {
+                                    flush(&pool, &inflight, &mut buf).await;
+                                }
+                            }
+                            None => {
+                                flush(&pool, &inflight, &mut buf).await;
+                                break;
+                            }
+                        }
+                    }
+                    _ = ticker.tick() => {
+                        flush(&pool, &inflight, &mut buf).await;
+                    }
+                }
+            }
+        });
+
+        (tx, handle)
+    }
+
+    /// Releases every `apelido` in `batch` from `inflight`, regardless of
+    /// whether it ended up inserted, dropped by `on conflict`, or never
+    /// reached the database at all — once a batch resolves one way or
+    /// another, the claim has served its purpose.
+    fn release(inflight: &InflightSet, batch: &[FlushItem]) {
+        let mut guard = inflight.lock().unwrap();
+        for (_, p) in batch {
+            guard.remove(&p.apelido);
+        }
+    }
+
+    async fn flush(pool: &Pool, inflight: &InflightSet, buf: &mut Vec<FlushItem>) {
+        if buf.is_empty() {
+            return;
+        }
+
+        // Keep only the last write per `apelido` in this batch so a
+        // duplicate inside the batch can't abort the whole multi-row insert.
+        let mut deduped: Vec<FlushItem> = Vec::with_capacity(buf.len());
+        for item in buf.drain(..) {
+            match deduped.iter().position(|(_, p)| p.apelido == item.1.apelido) {
+                Some(pos) => deduped[pos] = item,
+                None => deduped.push(item),
+            }
+        }
+
+        let client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("flusher: couldn't get a db connection, dropping batch of {}: {e}", deduped.len());
+                release(inflight, &deduped);
+                return;
+            }
+        };
+
+        let mut sql = String::from("insert into people (id, apelido, nome, nascimento, stack) values ");
+        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> =
+            Vec::with_capacity(deduped.len() * 5);
+
+        for (i, (id, p)) in deduped.iter().enumerate() {
+            if i > 0 {
+                sql.push(',');
+            }
+            let base = i * 5;
+            sql.push_str(&format!(
+                " (${}, ${}, ${}, ${}, ${})",
+                base + 1,
+                base + 2,
+                base + 3,
+                base + 4,
+                base + 5
+            ));
+            params.push(id);
+            params.push(&p.apelido);
+            params.push(&p.nome);
+            params.push(&p.nascimento);
+            params.push(&p.stack);
+        }
+        sql.push_str(" on conflict (apelido) do nothing returning id");
+
+        let rows = match client.query(sql.as_str(), &params).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                eprintln!("flusher: batch insert of {} rows failed: {e}", deduped.len());
+                release(inflight, &deduped);
+                return;
+            }
+        };
+
+        // `on conflict do nothing` means `rows` only reports the ids that
+        // actually landed; anything dropped by the conflict must not be
+        // notified as if it existed. Either way, every claim in this batch
+        // has now been resolved.
+        let inserted: HashSet<Uuid> = rows.iter().map(|row| row.get("id")).collect();
+        release(inflight, &deduped);
+        if inserted.is_empty() {
+            return;
+        }
+
+        // Tell every other instance to evict the key: this instance's own
+        // `get()` never populated its cache for a row it just inserted (only
+        // reads do that), so there's nothing local to warm here.
+        for (id, _) in &deduped {
+            if !inserted.contains(id) {
+                continue;
+            }
+            if let Err(e) = client
+                .query("select pg_notify('people_changed', $1)", &[&id.to_string()])
+                .await
+            {
+                eprintln!("flusher: failed to notify for {id}: {e}");
+            }
+        }
+    }
+}
+
+// Read cache for `get_person`, kept coherent across horizontally-scaled
+// instances via Postgres LISTEN/NOTIFY rather than a TTL.
+mod cache {
+    use std::num::NonZeroUsize;
+
+    use lru::LruCache;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    use crate::models::PersonOutput;
+
+    const CAPACITY: usize = 10_000;
+
+    pub type PersonCache = RwLock<LruCache<Uuid, PersonOutput>>;
+
+    pub fn new() -> PersonCache {
+        RwLock::new(LruCache::new(NonZeroUsize::new(CAPACITY).unwrap()))
+    }
+}
+
+// Keeps the local `PersonCache` coherent with writes made by other
+// instances: every instance opens one dedicated (non-pooled) connection,
+// LISTENs on `people_changed`, and evicts whatever key it's told about.
+mod listener {
+    use std::{sync::Arc, time::Duration};
+
+    use deadpool_postgres::tokio_postgres::{AsyncMessage, Config, NoTls};
+    use futures::stream::{self, StreamExt};
+    use uuid::Uuid;
+
+    use crate::cache::PersonCache;
+
+    pub async fn listen(pg_config: Config, cache: Arc<PersonCache>) {
+        loop {
+            match pg_config.connect(NoTls).await {
+                Ok((client, mut connection)) => {
+                    // The connection must be polled for `batch_execute` below
+                    // to complete, so it is driven (and its notifications
+                    // handled) on its own task while `client` stays alive here.
+                    let cache = cache.clone();
+                    let driver = tokio::spawn(async move {
+                        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+                        while let Some(message) = messages.next().await {
+                            match message {
+                                Ok(AsyncMessage::Notification(n)) => {
+                                    if let Ok(id) = Uuid::parse_str(n.payload()) {
+                                        cache.write().await.pop(&id);
+                                    }
+                                }
+                                Ok(_) => {}
+                                Err(e) => {
+                                    eprintln!("listener: connection error: {e}");
+                                    break;
+                                }
+                            }
+                        }
+                    });
+
+                    // Don't `continue` straight back to `connect` here: that
+                    // would skip the shared backoff sleep below and busy-loop
+                    // reconnect/LISTEN attempts against a database that's
+                    // rejecting them.
+                    if let Err(e) = client.batch_execute("LISTEN people_changed").await {
+                        eprintln!("listener: failed to LISTEN: {e}");
+                        driver.abort();
+                    } else {
+                        let _ = driver.await;
+                    }
+                }
+                Err(e) => eprintln!("listener: failed to connect: {e}"),
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
 pub mod models {
     use chrono::NaiveDate;
     use serde::{Deserialize, Serialize};
@@ -22,7 +268,7 @@ pub mod models {
         pub stack: Option<Vec<String>>,
     }
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Clone)]
     pub struct PersonOutput {
         pub id: String,
         pub apelido: String,
@@ -34,21 +280,25 @@ pub mod models {
     #[derive(Deserialize, Debug)]
     pub struct Q {
         pub t: String,
+        pub limit: Option<i64>,
     }
 }
 
 pub mod handlers {
-    use actix_web::{web, Error, HttpRequest, HttpResponse};
-    use deadpool_postgres::{Client, Pool};
+    use std::sync::Arc;
+
+    use actix_web::{web, HttpResponse};
     use futures::StreamExt;
     use uuid::Uuid;
 
-    use crate::models::{PersonInput, PersonOutput, Q};
+    use crate::error::MyError;
+    use crate::models::{PersonInput, Q};
+    use crate::repo::PeopleRepo;
 
     pub async fn create_person(
-        req: HttpRequest,
         mut payload: web::Payload,
-    ) -> Result<HttpResponse, Error> {
+        repo: web::Data<Arc<dyn PeopleRepo>>,
+    ) -> Result<HttpResponse, MyError> {
         // Deserialize body
         let mut body = web::BytesMut::new();
         while let Some(chunk) = payload.next().await {
@@ -57,152 +307,115 @@ pub mod handlers {
         }
 
         let Ok(p) = serde_json::from_slice::<PersonInput>(&body) else {
-            return Err(actix_web::error::ErrorUnprocessableEntity("Unprocessable Entity"));
+            return Err(MyError::Unprocessable);
         };
 
-        let pool = req.app_data::<web::Data<Pool>>().unwrap();
-        let client: Client = pool.get().await.unwrap();
-
-        let stmt = client
-            .prepare_cached("insert into people (id, apelido, nome, nascimento, stack) values ($1, $2, $3, $4, $5)")
-            .await.unwrap();
-
+        // The id is generated here, client-side of the DB, so the response
+        // doesn't have to wait on a round-trip.
         let id = Uuid::new_v4();
-
-        if let Err(e) = client
-            .query(&stmt, &[&id, &p.apelido, &p.nome, &p.nascimento, &p.stack])
-            .await
-        {
-            return match e.code() {
-                Some(code) => match code {
-                    &tokio_postgres::error::SqlState::UNIQUE_VIOLATION => Err(
-                        actix_web::error::ErrorUnprocessableEntity("Unprocessable Entity"),
-                    ),
-                    &tokio_postgres::error::SqlState::INTEGRITY_CONSTRAINT_VIOLATION => Err(
-                        actix_web::error::ErrorUnprocessableEntity("Unprocessable Entity"),
-                    ),
-                    _ => Err(actix_web::error::ErrorInternalServerError(
-                        "Internal Server Error",
-                    )),
-                },
-                None => Err(actix_web::error::ErrorInternalServerError(
-                    "Internal Server Error",
-                )),
-            };
-        }
+        repo.insert(id, p).await?;
 
         Ok(HttpResponse::Created()
             .insert_header(("Location", format!("/pessoas/{id}")))
             .finish())
     }
 
-    pub async fn get_person(id: web::Path<String>, db_pool: web::Data<Pool>) -> HttpResponse {
-        let client: Client = db_pool.get().await.unwrap();
-        let person_id = Uuid::parse_str(id.into_inner().as_str()).unwrap();
-
-        let stmt = client
-            .prepare_cached("select id, apelido, nome, nascimento, stack from people where id = $1")
-            .await
-            .unwrap();
-
-        let opt_row = client.query_opt(&stmt, &[&person_id]).await.unwrap();
-
-        match opt_row {
-            Some(row) => {
-                let person_id: Uuid = row.get("id");
-                let person = PersonOutput {
-                    id: person_id.to_string(),
-                    apelido: row.get("apelido"),
-                    nome: row.get("nome"),
-                    nascimento: row.get("nascimento"),
-                    stack: row.get("stack"),
-                };
-                HttpResponse::Ok().json(person)
-            }
-            None => HttpResponse::NotFound().finish(),
+    pub async fn get_person(
+        id: web::Path<String>,
+        repo: web::Data<Arc<dyn PeopleRepo>>,
+    ) -> Result<HttpResponse, MyError> {
+        let person_id = Uuid::parse_str(id.into_inner().as_str())?;
+
+        match repo.get(person_id).await? {
+            Some(person) => Ok(HttpResponse::Ok().json(person)),
+            None => Err(MyError::NotFound),
         }
     }
 
-    pub async fn search_people(query: web::Query<Q>, db_pool: web::Data<Pool>) -> HttpResponse {
-        let client: Client = db_pool.get().await.unwrap();
-
-        let stmt = client
-            .prepare_cached("select id, apelido, nome, nascimento, stack from people where for_search like ('%' || $1 || '%') ")
-            .await
-            .unwrap();
-
-        let rows = client.query(&stmt, &[&query.t]).await.unwrap();
-
-        let people: Vec<PersonOutput> = rows
-            .iter()
-            .map(|row| {
-                let person_id: Uuid = row.get("id");
-                PersonOutput {
-                    id: person_id.to_string(),
-                    apelido: row.get("apelido"),
-                    nome: row.get("nome"),
-                    nascimento: row.get("nascimento"),
-                    stack: row.get("stack"),
-                }
-            })
-            .collect();
-
-        HttpResponse::Ok().json(people)
+    pub async fn search_people(
+        query: web::Query<Q>,
+        repo: web::Data<Arc<dyn PeopleRepo>>,
+    ) -> Result<HttpResponse, MyError> {
+        let limit = query.limit.unwrap_or(crate::repo::DEFAULT_SEARCH_LIMIT);
+        let people = repo.search(&query.t, limit).await?;
+        Ok(HttpResponse::Ok().json(people))
     }
 
-    pub async fn count_people(db_pool: web::Data<Pool>) -> HttpResponse {
-        let client: Client = db_pool.get().await.unwrap();
-        let stmt = client
-            .prepare_cached("select count(1)::TEXT from people")
-            .await
-            .unwrap();
-
-        let row = client.query_one(&stmt, &[]).await.unwrap();
-        let count: String = row.get(0);
-
-        HttpResponse::Ok().body(count)
+    pub async fn count_people(repo: web::Data<Arc<dyn PeopleRepo>>) -> Result<HttpResponse, MyError> {
+        let count = repo.count().await?;
+        Ok(HttpResponse::Ok().body(count.to_string()))
     }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // get database pool
-    let pg_config = Config::new()
-        .host(&var("DB_HOST").unwrap_or("localhost".into()))
-        .user(&var("DB_USER").unwrap_or("postgres".into()))
-        .dbname(&var("DB_NAME").unwrap_or("postgres".into()))
-        .password(&var("DB_PASS").unwrap_or("password".into()))
-        .to_owned();
-
-    let manager_config = ManagerConfig {
-        recycling_method: RecyclingMethod::Fast,
-    };
-    let manager = Manager::from_config(pg_config, NoTls, manager_config);
-
-    let pool = Pool::builder(manager).max_size(16).build().unwrap();
+    let config = Configuration::from_env();
+
+    // `shutdown` carries the flusher's sender/handle when we're running
+    // against Postgres, so we can drain the write-behind buffer on the way
+    // out; the in-memory backend has nothing to flush.
+    let (repo, shutdown): (Arc<dyn PeopleRepo>, Option<(flusher::FlushSender, JoinHandle<()>)>) =
+        if config.storage == "memory" {
+            (Arc::new(InMemoryRepo::new()), None)
+        } else {
+            let pg_config = config.pg_config();
+
+            let manager_config = ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            };
+            let manager = Manager::from_config(pg_config.clone(), NoTls, manager_config);
+
+            let pool = Pool::builder(manager)
+                .max_size(config.db_pool_size)
+                .build()
+                .unwrap();
+
+            // sync run migrations
+            let mut conn = pool.get().await.unwrap();
+            let client = conn.deref_mut().deref_mut();
+            embedded::migrations::runner()
+                .run_async(client)
+                .await
+                .unwrap();
+
+            let person_cache = Arc::new(cache::new());
+            tokio::spawn(listener::listen(pg_config, person_cache.clone()));
+
+            let inflight = Arc::new(flusher::InflightSet::default());
+            let (flush_tx, flush_handle) = flusher::spawn(pool.clone(), inflight.clone());
+            let repo: Arc<dyn PeopleRepo> =
+                Arc::new(PostgresRepo::new(pool, person_cache, flush_tx.clone(), inflight));
+
+            (repo, Some((flush_tx, flush_handle)))
+        };
 
-    // sync run migrations
-    let mut conn = pool.get().await.unwrap();
-    let client = conn.deref_mut().deref_mut();
-    embedded::migrations::runner()
-        .run_async(client)
-        .await
-        .unwrap();
+    let workers = config.workers;
+    let http_port = config.http_port;
 
     // configure server
     let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(repo.clone()))
             .route("/pessoas", web::post().to(create_person))
             .route("/pessoas/{id}", web::get().to(get_person))
             .route("/pessoas", web::get().to(search_people))
             .route("/contagem-pessoas", web::get().to(count_people))
     })
-    .bind(("0.0.0.0", 80))?
+    .workers(workers)
+    .bind(("0.0.0.0", http_port))?
     .run();
 
     println!("Will listen");
 
     // start server
-    server.await
+    server.await?;
+
+    if let Some((flush_tx, flush_handle)) = shutdown {
+        // dropping our sender lets the flusher observe channel closure,
+        // flush whatever is left in the buffer, and exit
+        drop(flush_tx);
+        flush_handle.await.unwrap();
+    }
+
+    Ok(())
 }
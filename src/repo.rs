@@ -0,0 +1,217 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use deadpool_postgres::{Client, Pool};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::cache::PersonCache;
+use crate::error::MyError;
+use crate::flusher::{FlushSender, InflightSet};
+use crate::models::{PersonInput, PersonOutput};
+
+/// Default `limit` for `search` when the caller doesn't pass one.
+pub const DEFAULT_SEARCH_LIMIT: i64 = 50;
+
+/// Upper bound on `search`'s `limit`, so a caller can't force an
+/// effectively unbounded scan by passing a huge value.
+pub const MAX_SEARCH_LIMIT: i64 = 100;
+
+/// Everything handlers need from storage, so they can be written against
+/// `Arc<dyn PeopleRepo>` instead of a concrete Postgres pool.
+#[async_trait]
+pub trait PeopleRepo: Send + Sync {
+    async fn insert(&self, id: Uuid, person: PersonInput) -> Result<(), MyError>;
+    async fn get(&self, id: Uuid) -> Result<Option<PersonOutput>, MyError>;
+    async fn search(&self, term: &str, limit: i64) -> Result<Vec<PersonOutput>, MyError>;
+    async fn count(&self) -> Result<i64, MyError>;
+}
+
+/// Repository backed by Postgres, fronted by the write-behind flusher (see
+/// [`crate::flusher`]) and the LISTEN/NOTIFY-invalidated read cache (see
+/// [`crate::cache`]).
+pub struct PostgresRepo {
+    pool: Pool,
+    cache: Arc<PersonCache>,
+    flush_tx: FlushSender,
+    inflight: Arc<InflightSet>,
+}
+
+impl PostgresRepo {
+    pub fn new(pool: Pool, cache: Arc<PersonCache>, flush_tx: FlushSender, inflight: Arc<InflightSet>) -> Self {
+        Self {
+            pool,
+            cache,
+            flush_tx,
+            inflight,
+        }
+    }
+}
+
+#[async_trait]
+impl PeopleRepo for PostgresRepo {
+    async fn insert(&self, id: Uuid, person: PersonInput) -> Result<(), MyError> {
+        // The flusher only enqueues; by the time it runs, the 201 with the
+        // Location header is long gone. So an `apelido` that's already
+        // committed has to be rejected here, synchronously, rather than
+        // silently dropped later by the batch's `on conflict do nothing`.
+        let client: Client = self.pool.get().await?;
+        let stmt = client
+            .prepare_cached("select 1 from people where apelido = $1")
+            .await?;
+        if client.query_opt(&stmt, &[&person.apelido]).await?.is_some() {
+            return Err(MyError::Unprocessable);
+        }
+
+        // The check above only catches an `apelido` that's already committed;
+        // two concurrent requests for the same never-before-seen `apelido`
+        // would both pass it and both enqueue. Claiming it here, atomically,
+        // closes that window — the loser gets its 422 up front instead of
+        // silently vanishing when the batch's `on conflict do nothing` drops
+        // one of the two inserts. The flusher releases the claim once the
+        // batch containing it resolves, win or lose.
+        let apelido = person.apelido.clone();
+        if !self.inflight.lock().unwrap().insert(apelido.clone()) {
+            return Err(MyError::Unprocessable);
+        }
+
+        if self.flush_tx.send((id, person)).await.is_err() {
+            self.inflight.lock().unwrap().remove(&apelido);
+            return Err(MyError::Unavailable);
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<PersonOutput>, MyError> {
+        if let Some(person) = self.cache.read().await.peek(&id) {
+            return Ok(Some(person.clone()));
+        }
+
+        let client: Client = self.pool.get().await?;
+        let stmt = client
+            .prepare_cached("select id, apelido, nome, nascimento, stack from people where id = $1")
+            .await?;
+        let opt_row = client.query_opt(&stmt, &[&id]).await?;
+
+        let person = opt_row.map(|row| {
+            let person_id: Uuid = row.get("id");
+            PersonOutput {
+                id: person_id.to_string(),
+                apelido: row.get("apelido"),
+                nome: row.get("nome"),
+                nascimento: row.get("nascimento"),
+                stack: row.get("stack"),
+            }
+        });
+
+        if let Some(person) = &person {
+            self.cache.write().await.put(id, person.clone());
+        }
+
+        Ok(person)
+    }
+
+    async fn search(&self, term: &str, limit: i64) -> Result<Vec<PersonOutput>, MyError> {
+        let client: Client = self.pool.get().await?;
+        // Filtering stays on `LIKE` (still accelerated by the `gin_trgm_ops`
+        // GIN index): the `%` similarity operator only matches above a
+        // similarity threshold, so a substring that's genuinely present in
+        // `for_search` can come back empty for short or sparse terms.
+        // `similarity()` is used purely to rank what `LIKE` already found.
+        let limit = limit.clamp(0, MAX_SEARCH_LIMIT);
+        let stmt = client
+            .prepare_cached(
+                "select id, apelido, nome, nascimento, stack \
+                 from people \
+                 where for_search like ('%' || $1 || '%') \
+                 order by similarity(for_search, $1) desc \
+                 limit $2",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&term, &limit]).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let person_id: Uuid = row.get("id");
+                PersonOutput {
+                    id: person_id.to_string(),
+                    apelido: row.get("apelido"),
+                    nome: row.get("nome"),
+                    nascimento: row.get("nascimento"),
+                    stack: row.get("stack"),
+                }
+            })
+            .collect())
+    }
+
+    async fn count(&self) -> Result<i64, MyError> {
+        let client: Client = self.pool.get().await?;
+        let stmt = client.prepare_cached("select count(1) from people").await?;
+        let row = client.query_one(&stmt, &[]).await?;
+        Ok(row.get(0))
+    }
+}
+
+/// All-in-memory repository, selected with `STORAGE=memory`, for running
+/// this challenge-style service without standing up a database.
+#[derive(Default)]
+pub struct InMemoryRepo {
+    people: RwLock<HashMap<Uuid, PersonOutput>>,
+}
+
+impl InMemoryRepo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl PeopleRepo for InMemoryRepo {
+    async fn insert(&self, id: Uuid, person: PersonInput) -> Result<(), MyError> {
+        let mut people = self.people.write().await;
+        if people.values().any(|p| p.apelido == person.apelido) {
+            return Err(MyError::Unprocessable);
+        }
+
+        people.insert(
+            id,
+            PersonOutput {
+                id: id.to_string(),
+                apelido: person.apelido,
+                nome: person.nome,
+                nascimento: person.nascimento,
+                stack: person.stack,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<PersonOutput>, MyError> {
+        Ok(self.people.read().await.get(&id).cloned())
+    }
+
+    async fn search(&self, term: &str, limit: i64) -> Result<Vec<PersonOutput>, MyError> {
+        let term = term.to_lowercase();
+        let people = self.people.read().await;
+
+        Ok(people
+            .values()
+            .filter(|p| {
+                p.apelido.to_lowercase().contains(&term)
+                    || p.nome.to_lowercase().contains(&term)
+                    || p.stack
+                        .as_ref()
+                        .is_some_and(|stack| stack.iter().any(|s| s.to_lowercase().contains(&term)))
+            })
+            .take(limit.clamp(0, MAX_SEARCH_LIMIT) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn count(&self) -> Result<i64, MyError> {
+        Ok(self.people.read().await.len() as i64)
+    }
+}